@@ -1,12 +1,46 @@
 use rust_decimal::Decimal;
 use rust_decimal::prelude::Zero;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Display, Formatter};
+use std::io;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum LedgerError {
+    #[error(
+        "Unable to process the withdrawal or dispute of {requested} for client with id {client}: available funds {available}"
+    )]
+    NotEnoughFunds {
+        client: u16,
+        requested: Decimal,
+        available: Decimal,
+    },
+    #[error("Transaction with id {0} does not exist")]
+    UnknownTx(u32),
+    #[error("Transaction with id {tx} does not correspond to client with id '{client}'")]
+    WrongClientForTx { tx: u32, client: u16 },
+    #[error("Cannot apply '{op}' to transaction {tx} in state '{from}'")]
+    InvalidStateTransition {
+        tx: u32,
+        from: TxState,
+        op: TransactionType,
+    },
+    #[error("Transaction {0} has already been charged back")]
+    AlreadyChargedBack(u32),
+    #[error("Cannot process a transaction with a duplicated transaction id {0}")]
+    DuplicateTx(u32),
+    #[error("{0}s must have an amount. Input CSV format is wrong")]
+    MissingAmount(TransactionType),
+    #[error("{0}s must not carry an amount. Input CSV format is wrong")]
+    UnexpectedAmount(TransactionType),
+    #[error("Account of client with id {0} is locked")]
+    FrozenAccount(u16),
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
 #[serde(rename_all(deserialize = "lowercase"))]
-enum TransactionType {
+pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
@@ -26,22 +60,59 @@ impl Display for TransactionType {
     }
 }
 
-impl TransactionType {
-    fn check_state_transition(&self, previous_state: &TransactionType) -> Result<(), String> {
-        match (self, previous_state) {
-            (TransactionType::Dispute, TransactionType::Deposit) => Ok(()),
-            (TransactionType::ChargeBack, TransactionType::Dispute) => Ok(()),
-            (TransactionType::Resolve, TransactionType::Dispute) => Ok(()),
-            _ => Err(format!(
-                "Invalid state transition from '{}' to '{}'",
-                previous_state, self
-            )),
+/// The lifecycle stage of a stored transaction. A deposit or withdrawal starts
+/// out `Processed`; a dispute moves it to `Disputed`, from which it can either
+/// be `Resolved` or `ChargedBack`. A resolved transaction may be disputed again,
+/// while `ChargedBack` is terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxState {
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl Display for TxState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TxState::Processed => write!(f, "processed"),
+            TxState::Disputed => write!(f, "disputed"),
+            TxState::Resolved => write!(f, "resolved"),
+            TxState::ChargedBack => write!(f, "charged back"),
         }
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
-pub struct Transaction {
+/// Why a requested lifecycle transition was rejected; mapped onto a
+/// [`LedgerError`] with the offending transaction id by the caller.
+#[derive(Debug, PartialEq)]
+enum TransitionError {
+    AlreadyChargedBack,
+    Invalid,
+}
+
+impl TxState {
+    /// Compute the state that applying `op` (a dispute, resolve, or chargeback)
+    /// would move this transaction into, or why the operation is not allowed.
+    fn transition(self, op: TransactionType) -> Result<TxState, TransitionError> {
+        match (self, op) {
+            (TxState::Processed, TransactionType::Dispute) => Ok(TxState::Disputed),
+            (TxState::Resolved, TransactionType::Dispute) => Ok(TxState::Disputed),
+            (TxState::Disputed, TransactionType::Resolve) => Ok(TxState::Resolved),
+            (TxState::Disputed, TransactionType::ChargeBack) => Ok(TxState::ChargedBack),
+            (TxState::ChargedBack, _) => Err(TransitionError::AlreadyChargedBack),
+            _ => Err(TransitionError::Invalid),
+        }
+    }
+}
+
+/// A single CSV row as it appears on disk, before per-variant validation.
+///
+/// The `amount` column is optional so that dispute/resolve/chargeback rows may
+/// omit it; `TryFrom` below turns this loose shape into a strongly typed
+/// [`Transaction`], rejecting rows whose amount does not match their type.
+#[derive(Debug, Deserialize)]
+struct TransactionRecord {
     #[serde(rename(deserialize = "type"))]
     transaction_type: TransactionType,
     client: u16,
@@ -49,62 +120,199 @@ pub struct Transaction {
     amount: Option<Decimal>,
 }
 
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(try_from = "TransactionRecord")]
+pub enum Transaction {
+    Deposit { client: u16, tx: u32, amount: Decimal },
+    Withdrawal { client: u16, tx: u32, amount: Decimal },
+    Dispute { client: u16, tx: u32 },
+    Resolve { client: u16, tx: u32 },
+    Chargeback { client: u16, tx: u32 },
+}
+
+impl Transaction {
+    /// The client this transaction belongs to. Used to shard work, since every
+    /// transaction (and the account it affects) is owned by exactly one client.
+    pub fn client(&self) -> u16 {
+        match *self {
+            Transaction::Deposit { client, .. }
+            | Transaction::Withdrawal { client, .. }
+            | Transaction::Dispute { client, .. }
+            | Transaction::Resolve { client, .. }
+            | Transaction::Chargeback { client, .. } => client,
+        }
+    }
+
+    /// The transaction id this record carries.
+    pub fn tx(&self) -> u32 {
+        match *self {
+            Transaction::Deposit { tx, .. }
+            | Transaction::Withdrawal { tx, .. }
+            | Transaction::Dispute { tx, .. }
+            | Transaction::Resolve { tx, .. }
+            | Transaction::Chargeback { tx, .. } => tx,
+        }
+    }
+
+    /// Whether this record introduces a new transaction id. Deposits and
+    /// withdrawals do; dispute/resolve/chargeback reference an existing one.
+    /// Only the introducing records are subject to duplicate-id rejection.
+    pub fn introduces_tx_id(&self) -> bool {
+        matches!(
+            self,
+            Transaction::Deposit { .. } | Transaction::Withdrawal { .. }
+        )
+    }
+}
+
+impl TryFrom<TransactionRecord> for Transaction {
+    type Error = LedgerError;
+
+    fn try_from(record: TransactionRecord) -> Result<Self, Self::Error> {
+        let TransactionRecord {
+            transaction_type,
+            client,
+            tx,
+            amount,
+        } = record;
+
+        match transaction_type {
+            TransactionType::Deposit => {
+                let amount = amount.ok_or(LedgerError::MissingAmount(transaction_type))?;
+                Ok(Transaction::Deposit { client, tx, amount })
+            }
+            TransactionType::Withdrawal => {
+                let amount = amount.ok_or(LedgerError::MissingAmount(transaction_type))?;
+                Ok(Transaction::Withdrawal { client, tx, amount })
+            }
+            TransactionType::Dispute => match amount {
+                None => Ok(Transaction::Dispute { client, tx }),
+                Some(_) => Err(LedgerError::UnexpectedAmount(transaction_type)),
+            },
+            TransactionType::Resolve => match amount {
+                None => Ok(Transaction::Resolve { client, tx }),
+                Some(_) => Err(LedgerError::UnexpectedAmount(transaction_type)),
+            },
+            TransactionType::ChargeBack => match amount {
+                None => Ok(Transaction::Chargeback { client, tx }),
+                Some(_) => Err(LedgerError::UnexpectedAmount(transaction_type)),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct AccountStatus {
+    pub client: u16,
+    #[serde(serialize_with = "serialize_rounded")]
     pub available: Decimal,
+    #[serde(serialize_with = "serialize_rounded")]
     pub held: Decimal,
+    #[serde(serialize_with = "serialize_rounded")]
     pub total: Decimal,
     pub locked: bool,
 }
 
+/// Render a balance with the fixed four decimal places the output format expects.
+fn serialize_rounded<S>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let mut rounded = value.round_dp(4);
+    rounded.rescale(4);
+    rounded.serialize(serializer)
+}
+
 #[derive(Debug)]
 struct TransactionInformation {
     client: u16,
     amount: Decimal,
-    state: TransactionType,
+    state: TxState,
 }
 
 pub struct Ledger {
     transactions: HashMap<u32, TransactionInformation>,
     client_accounts: HashMap<u16, AccountStatus>,
+    /// When `true`, a dispute holds the disputed amount even if it drives
+    /// `available` negative (lenient accounting); when `false`, such a dispute
+    /// is rejected instead (strict accounting).
+    allow_negative_balances: bool,
 }
 
 impl Ledger {
     pub fn new() -> Self {
+        Self::with_negative_balances(true)
+    }
+
+    /// Build a ledger, choosing whether disputes may drive `available` negative
+    /// (lenient) or must be rejected once funds are no longer available (strict).
+    pub fn with_negative_balances(allow_negative_balances: bool) -> Self {
         Ledger {
             transactions: HashMap::new(),
             client_accounts: HashMap::new(),
+            allow_negative_balances,
         }
     }
 
-    fn handle_transaction_transition(
-        &mut self,
+    /// Validate that `op` may be applied to transaction `tx_id` on behalf of
+    /// `client_id`, returning the stored amount and the state the transaction
+    /// would move into. Performs no mutation, so callers can run further guards
+    /// (e.g. the strict funds check) before committing with [`Self::apply_transition`].
+    fn check_transition(
+        &self,
         tx_id: u32,
         client_id: u16,
-        new_state: TransactionType,
-    ) -> Result<&TransactionInformation, String> {
-        let Some(tx) = self.transactions.get_mut(&tx_id) else {
-            return Err(format!("Transaction with id {} does not exist", tx_id));
+        op: TransactionType,
+    ) -> Result<(Decimal, TxState), LedgerError> {
+        let Some(tx) = self.transactions.get(&tx_id) else {
+            return Err(LedgerError::UnknownTx(tx_id));
         };
 
         if tx.client != client_id {
-            return Err(format!(
-                "Transaction with id {} does not correspond to client with id '{}'",
-                tx_id, client_id
-            ));
-        };
-        new_state.check_state_transition(&tx.state)?;
+            return Err(LedgerError::WrongClientForTx {
+                tx: tx_id,
+                client: client_id,
+            });
+        }
+
+        let new_state = tx.state.transition(op).map_err(|e| match e {
+            TransitionError::AlreadyChargedBack => LedgerError::AlreadyChargedBack(tx_id),
+            TransitionError::Invalid => LedgerError::InvalidStateTransition {
+                tx: tx_id,
+                from: tx.state,
+                op,
+            },
+        })?;
+
+        Ok((tx.amount, new_state))
+    }
 
-        tx.state = new_state;
+    /// Commit a state transition previously validated by [`Self::check_transition`].
+    fn apply_transition(&mut self, tx_id: u32, new_state: TxState) {
+        if let Some(tx) = self.transactions.get_mut(&tx_id) {
+            tx.state = new_state;
+        }
+    }
 
-        Ok(tx)
+    fn handle_transaction_transition(
+        &mut self,
+        tx_id: u32,
+        client_id: u16,
+        op: TransactionType,
+    ) -> Result<&TransactionInformation, LedgerError> {
+        let (_, new_state) = self.check_transition(tx_id, client_id, op)?;
+        self.apply_transition(tx_id, new_state);
+        Ok(self
+            .transactions
+            .get(&tx_id)
+            .expect("transaction was just transitioned"))
     }
 
-    fn check_account_is_locked(&self, client_id: u16) -> Result<(), String> {
+    fn check_account_is_locked(&self, client_id: u16) -> Result<(), LedgerError> {
         if let Some(account) = self.client_accounts.get(&client_id)
             && account.locked
         {
-            return Err(format!("Account of client with id {} is locked", client_id));
+            return Err(LedgerError::FrozenAccount(client_id));
         }
         Ok(())
     }
@@ -114,48 +322,45 @@ impl Ledger {
         tx_id: u32,
         amount: Decimal,
         client_id: u16,
-    ) -> Result<(), String> {
-        // Assumption or comes in the document?
-        if let Some(_) = self.transactions.get(&tx_id) {
-            return Err(format!(
-                "Cannot process a deposit with a duplicated transaction id {} ",
-                tx_id
-            ));
-        }
-
+    ) -> Result<(), LedgerError> {
+        // The duplicate-id check lives in the deposit and withdrawal arms, which
+        // reject a repeated id before mutating any balance; by the time we get
+        // here the id is known to be new.
         self.transactions.insert(
             tx_id,
             TransactionInformation {
                 client: client_id,
                 amount,
-                state: TransactionType::Deposit,
+                state: TxState::Processed,
             },
         );
 
         Ok(())
     }
 
-    pub(crate) fn handle_new_transaction(&mut self, transaction: &Transaction) -> Result<(), String> {
-        match transaction.transaction_type {
-            TransactionType::Deposit => {
-                let Some(amount) = transaction.amount else {
-                    return Err(
-                        "<Deposits must have an amount. Input CSV format is wrong>".to_string()
-                    );
-                };
-                if let Some(account) = self.client_accounts.get_mut(&transaction.client) {
+    pub(crate) fn handle_new_transaction(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(), LedgerError> {
+        match *transaction {
+            Transaction::Deposit { client, tx, amount } => {
+                // Reject a duplicated id before touching balances, so a repeated
+                // deposit id cannot double-credit the account and only then be
+                // rejected (mirrors the withdrawal arm below).
+                if self.transactions.contains_key(&tx) {
+                    return Err(LedgerError::DuplicateTx(tx));
+                }
+                if let Some(account) = self.client_accounts.get_mut(&client) {
                     if account.locked {
-                        return Err(format!(
-                            "Account of client with id {} is locked",
-                            transaction.client
-                        ));
+                        return Err(LedgerError::FrozenAccount(client));
                     }
                     account.available += amount;
                     account.total += amount;
                 } else {
                     self.client_accounts.insert(
-                        transaction.client,
+                        client,
                         AccountStatus {
+                            client,
                             available: amount,
                             held: Decimal::zero(),
                             total: amount,
@@ -163,97 +368,97 @@ impl Ledger {
                         },
                     );
                 }
-                self.store_new_transaction(transaction.tx, amount, transaction.client)?
+                self.store_new_transaction(tx, amount, client)?
             }
-            TransactionType::Withdrawal => {
-                let Some(amount) = transaction.amount else {
-                    return Err(
-                        "<Withdrawals must have an amount. Input CSV format is wrong>".to_string(),
-                    );
+            Transaction::Withdrawal { client, tx, amount } => {
+                if self.transactions.contains_key(&tx) {
+                    return Err(LedgerError::DuplicateTx(tx));
+                }
+                let Some(account) = self.client_accounts.get_mut(&client) else {
+                    // No account to withdraw from: nothing happens, and there is
+                    // no transaction to record for a later dispute.
+                    return Ok(());
                 };
-                if let Some(_) = self.transactions.get(&transaction.tx) {
-                    return Err(format!(
-                        "Cannot process a withdrawal with a duplicated transaction id {} ",
-                        transaction.tx
-                    ));
+                if account.locked {
+                    return Err(LedgerError::FrozenAccount(client));
                 }
-                if let Some(account) = self.client_accounts.get_mut(&transaction.client) {
-                    if account.locked {
-                        return Err(format!(
-                            "Account of client with id {} is locked",
-                            transaction.client
-                        ));
-                    }
-                    if account.available < amount {
-                        return Err(format!(
-                            "Unable to process the withdrawal of {} for client with id {}: available funds {}",
-                            amount, transaction.client, account.available,
-                        ));
-                    }
-                    account.available -= amount;
-                    account.total -= amount;
+                // Hard invariant: a direct withdrawal can never exceed the funds
+                // currently available, regardless of the negative-balance policy.
+                if account.available < amount {
+                    return Err(LedgerError::NotEnoughFunds {
+                        client,
+                        requested: amount,
+                        available: account.available,
+                    });
                 }
+                account.available -= amount;
+                account.total -= amount;
+                // Record the withdrawal so it, too, can be disputed later.
+                self.store_new_transaction(tx, amount, client)?;
             }
-            TransactionType::Dispute => {
-                self.check_account_is_locked(transaction.client)?;
-
-                let amount = {
-                    let tx = self.handle_transaction_transition(
-                        transaction.tx,
-                        transaction.client,
-                        transaction.transaction_type,
-                    )?;
-                    tx.amount
-                };
-
-                if let Some(account) = self.client_accounts.get_mut(&transaction.client)
-                    && account.available >= amount
+            Transaction::Dispute { client, tx } => {
+                self.check_account_is_locked(client)?;
+
+                // Validate ownership and the state transition first, so a
+                // mis-addressed or non-disputable dispute yields its precise
+                // `WrongClientForTx` / `InvalidStateTransition` error rather than
+                // being masked by the funds guard below.
+                let (amount, new_state) =
+                    self.check_transition(tx, client, TransactionType::Dispute)?;
+
+                // Under strict accounting a dispute may not drive `available`
+                // negative, so reject it before committing. A dispute holds the
+                // disputed amount regardless of direction, so it always debits
+                // `available` by `amount`.
+                if !self.allow_negative_balances
+                    && let Some(account) = self.client_accounts.get(&client)
+                    && account.available < amount
                 {
+                    return Err(LedgerError::NotEnoughFunds {
+                        client,
+                        requested: amount,
+                        available: account.available,
+                    });
+                }
+
+                self.apply_transition(tx, new_state);
+
+                // Hold the disputed amount while the account is under scrutiny:
+                // `held` rises and `available` falls by the same amount (which may
+                // drive `available` negative), so `available` never exceeds `total`.
+                if let Some(account) = self.client_accounts.get_mut(&client) {
                     account.held += amount;
                     account.available -= amount;
                 }
             }
-            TransactionType::Resolve => {
-                self.check_account_is_locked(transaction.client)?;
-
-                let amount = {
-                    let tx = self.handle_transaction_transition(
-                        transaction.tx,
-                        transaction.client,
-                        transaction.transaction_type,
-                    )?;
-                    tx.amount
-                };
+            Transaction::Resolve { client, tx } => {
+                self.check_account_is_locked(client)?;
 
-                if let Some(account) = self.client_accounts.get_mut(&transaction.client)
-                    && account.held >= amount
-                {
+                let amount = self
+                    .handle_transaction_transition(tx, client, TransactionType::Resolve)?
+                    .amount;
+
+                if let Some(account) = self.client_accounts.get_mut(&client) {
                     account.held -= amount;
                     account.available += amount;
                 }
             }
-            TransactionType::ChargeBack => {
-                self.check_account_is_locked(transaction.client)?;
-
-                let amount = {
-                    let tx = self.handle_transaction_transition(
-                        transaction.tx,
-                        transaction.client,
-                        transaction.transaction_type,
-                    )?;
-                    tx.amount
-                };
+            Transaction::Chargeback { client, tx } => {
+                self.check_account_is_locked(client)?;
 
-                if let Some(account) = self.client_accounts.get_mut(&transaction.client) {
-                    if account.held >= amount {
-                        account.held -= amount;
-                        account.total -= amount;
-                    }
+                let amount = self
+                    .handle_transaction_transition(tx, client, TransactionType::ChargeBack)?
+                    .amount;
+
+                if let Some(account) = self.client_accounts.get_mut(&client) {
+                    account.held -= amount;
+                    account.total -= amount;
                     account.locked = true;
                 }
 
-                // Assumption: Charged back transactions are not required in the future.
-                self.transactions.remove(&transaction.tx);
+                // The transaction is kept in the map in its terminal `ChargedBack`
+                // state so that any further operation against it is reported as
+                // `AlreadyChargedBack` rather than as an unknown transaction.
             }
         }
         Ok(())
@@ -262,6 +467,46 @@ impl Ledger {
     pub fn client_accounts(&self) -> &HashMap<u16, AccountStatus> {
         &self.client_accounts
     }
+
+    /// Consume the ledger and return its per-client accounts. Used to merge the
+    /// private state of each worker shard back into a single output map; because
+    /// clients never span shards, the merged maps have disjoint keys.
+    pub fn into_client_accounts(self) -> HashMap<u16, AccountStatus> {
+        self.client_accounts
+    }
+
+    /// Build a ledger that owns an already-computed set of accounts, used to
+    /// reassemble the merged output of the worker shards for rendering.
+    pub fn from_accounts(client_accounts: HashMap<u16, AccountStatus>) -> Self {
+        Ledger {
+            transactions: HashMap::new(),
+            client_accounts,
+            allow_negative_balances: true,
+        }
+    }
+
+    /// Write every account as a CSV row in ascending `client` order, preceded by
+    /// the header. Accounts are collected into a [`BTreeMap`] first so output is
+    /// deterministic regardless of the underlying hash-map iteration order, and
+    /// each row is serialized straight from [`AccountStatus`].
+    pub fn write_csv<W: io::Write>(&self, w: &mut csv::Writer<W>) -> csv::Result<()> {
+        let ordered: BTreeMap<u16, &AccountStatus> = self
+            .client_accounts
+            .iter()
+            .map(|(client, account)| (*client, account))
+            .collect();
+
+        // `serialize` writes the header together with the first row only.
+        if ordered.is_empty() {
+            w.write_record(["client", "available", "held", "total", "locked"])?;
+        }
+
+        for account in ordered.values() {
+            w.serialize(account)?;
+        }
+
+        w.flush()
+    }
 }
 
 #[cfg(test)]
@@ -270,34 +515,69 @@ mod tests {
     use std::str::FromStr;
 
     #[test]
-    fn check_state_transitions() {
-        // Valid transitions
-        assert!(TransactionType::Dispute
-            .check_state_transition(&TransactionType::Deposit)
-            .is_ok());
-        assert!(TransactionType::Resolve
-            .check_state_transition(&TransactionType::Dispute)
-            .is_ok());
-        assert!(TransactionType::ChargeBack
-            .check_state_transition(&TransactionType::Dispute)
-            .is_ok());
-
-        // Invalid transitions
-        let result = TransactionType::Resolve.check_state_transition(&TransactionType::Deposit);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Invalid state transition"));
-
-        assert!(TransactionType::ChargeBack
-            .check_state_transition(&TransactionType::Deposit)
-            .is_err());
-
-        assert!(TransactionType::Dispute
-            .check_state_transition(&TransactionType::Withdrawal)
-            .is_err());
-
-        assert!(TransactionType::Dispute
-            .check_state_transition(&TransactionType::Resolve)
-            .is_err());
+    fn state_transitions() {
+        // Valid transitions, including re-disputing a resolved transaction.
+        assert_eq!(
+            TxState::Processed.transition(TransactionType::Dispute),
+            Ok(TxState::Disputed)
+        );
+        assert_eq!(
+            TxState::Disputed.transition(TransactionType::Resolve),
+            Ok(TxState::Resolved)
+        );
+        assert_eq!(
+            TxState::Disputed.transition(TransactionType::ChargeBack),
+            Ok(TxState::ChargedBack)
+        );
+        assert_eq!(
+            TxState::Resolved.transition(TransactionType::Dispute),
+            Ok(TxState::Disputed)
+        );
+
+        // Invalid transitions are rejected.
+        assert!(matches!(
+            TxState::Processed.transition(TransactionType::Resolve),
+            Err(TransitionError::Invalid)
+        ));
+        assert!(matches!(
+            TxState::Processed.transition(TransactionType::ChargeBack),
+            Err(TransitionError::Invalid)
+        ));
+
+        // A charged-back transaction is terminal.
+        assert!(matches!(
+            TxState::ChargedBack.transition(TransactionType::Dispute),
+            Err(TransitionError::AlreadyChargedBack)
+        ));
+    }
+
+    #[test]
+    fn write_csv_is_ordered_and_rounded() {
+        let mut ledger = Ledger::new();
+        // Insert out of client order to prove the output is sorted.
+        for client_id in [2u16, 1u16] {
+            ledger.client_accounts.insert(
+                client_id,
+                AccountStatus {
+                    client: client_id,
+                    available: Decimal::from_str("1.5").unwrap(),
+                    held: Decimal::zero(),
+                    total: Decimal::from_str("1.5").unwrap(),
+                    locked: false,
+                },
+            );
+        }
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        ledger.write_csv(&mut writer).unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap().to_vec()).unwrap();
+
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n\
+             1,1.5000,0.0000,1.5000,false\n\
+             2,1.5000,0.0000,1.5000,false\n"
+        );
     }
 
     #[test]
@@ -312,6 +592,7 @@ mod tests {
         ledger.client_accounts.insert(
             client_id,
             AccountStatus {
+                client: client_id,
                 available: Decimal::from_str("100").unwrap(),
                 held: Decimal::zero(),
                 total: Decimal::from_str("100").unwrap(),
@@ -323,8 +604,7 @@ mod tests {
         // Account exists and is locked
         ledger.client_accounts.get_mut(&client_id).unwrap().locked = true;
         let result = ledger.check_account_is_locked(client_id);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("is locked"));
+        assert_eq!(result, Err(LedgerError::FrozenAccount(client_id)));
     }
 
     #[test]
@@ -332,8 +612,7 @@ mod tests {
         let mut ledger = Ledger::new();
 
         let result = ledger.handle_transaction_transition(1, 1, TransactionType::Dispute);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("does not exist"));
+        assert_eq!(result.unwrap_err(), LedgerError::UnknownTx(1));
     }
 
     #[test]
@@ -348,13 +627,18 @@ mod tests {
             TransactionInformation {
                 client: original_client,
                 amount: Decimal::from_str("50").unwrap(),
-                state: TransactionType::Deposit,
+                state: TxState::Processed,
             },
         );
 
         let result = ledger.handle_transaction_transition(tx_id, wrong_client, TransactionType::Dispute);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("does not correspond to client"));
+        assert_eq!(
+            result.unwrap_err(),
+            LedgerError::WrongClientForTx {
+                tx: tx_id,
+                client: wrong_client,
+            }
+        );
     }
 
     #[test]
@@ -368,13 +652,19 @@ mod tests {
             TransactionInformation {
                 client: client_id,
                 amount: Decimal::from_str("50").unwrap(),
-                state: TransactionType::Deposit,
+                state: TxState::Processed,
             },
         );
 
         let result = ledger.handle_transaction_transition(tx_id, client_id, TransactionType::Resolve);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Invalid state transition"));
+        assert_eq!(
+            result.unwrap_err(),
+            LedgerError::InvalidStateTransition {
+                tx: tx_id,
+                from: TxState::Processed,
+                op: TransactionType::Resolve,
+            }
+        );
     }
 
     #[test]
@@ -389,7 +679,7 @@ mod tests {
             TransactionInformation {
                 client: client_id,
                 amount,
-                state: TransactionType::Deposit,
+                state: TxState::Processed,
             },
         );
 
@@ -398,6 +688,93 @@ mod tests {
 
         let tx = result.unwrap();
         assert_eq!(tx.amount, amount);
-        assert_eq!(tx.state, TransactionType::Dispute);
+        assert_eq!(tx.state, TxState::Disputed);
+    }
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn dispute_holds_even_when_funds_spent() {
+        let mut ledger = Ledger::new();
+        ledger
+            .handle_new_transaction(&Transaction::Deposit { client: 1, tx: 1, amount: dec("10") })
+            .unwrap();
+        ledger
+            .handle_new_transaction(&Transaction::Withdrawal { client: 1, tx: 2, amount: dec("10") })
+            .unwrap();
+        // The deposit is disputed after the money has already been withdrawn.
+        ledger
+            .handle_new_transaction(&Transaction::Dispute { client: 1, tx: 1 })
+            .unwrap();
+
+        let account = &ledger.client_accounts[&1];
+        assert_eq!(account.held, dec("10"));
+        assert_eq!(account.available, dec("-10"));
+        assert_eq!(account.total, dec("0"));
+    }
+
+    #[test]
+    fn strict_mode_rejects_overdrawing_dispute() {
+        let mut ledger = Ledger::with_negative_balances(false);
+        ledger
+            .handle_new_transaction(&Transaction::Deposit { client: 1, tx: 1, amount: dec("10") })
+            .unwrap();
+        ledger
+            .handle_new_transaction(&Transaction::Withdrawal { client: 1, tx: 2, amount: dec("10") })
+            .unwrap();
+
+        let result = ledger.handle_new_transaction(&Transaction::Dispute { client: 1, tx: 1 });
+        assert_eq!(
+            result,
+            Err(LedgerError::NotEnoughFunds {
+                client: 1,
+                requested: dec("10"),
+                available: dec("0"),
+            })
+        );
+
+        // The rejected dispute leaves the account untouched.
+        let account = &ledger.client_accounts[&1];
+        assert_eq!(account.held, dec("0"));
+        assert_eq!(account.available, dec("0"));
+    }
+
+    #[test]
+    fn withdrawals_can_be_disputed() {
+        let mut ledger = Ledger::new();
+        // Deposit 10, withdraw 4 -> available 6 / total 6.
+        ledger
+            .handle_new_transaction(&Transaction::Deposit { client: 1, tx: 1, amount: dec("10") })
+            .unwrap();
+        ledger
+            .handle_new_transaction(&Transaction::Withdrawal { client: 1, tx: 2, amount: dec("4") })
+            .unwrap();
+
+        // Disputing the withdrawal holds the amount: `held` rises and `available`
+        // falls by 4, and `total` is untouched by a dispute. The hold must never
+        // let `available` exceed `total`.
+        ledger
+            .handle_new_transaction(&Transaction::Dispute { client: 1, tx: 2 })
+            .unwrap();
+        let account = &ledger.client_accounts[&1];
+        assert_eq!(account.available, dec("2"));
+        assert_eq!(account.held, dec("4"));
+        assert_eq!(account.total, dec("6"));
+        assert!(account.available <= account.total);
+
+        // The held funds are frozen, not released: a further withdrawal larger
+        // than the remaining `available` is still rejected.
+        let result =
+            ledger.handle_new_transaction(&Transaction::Withdrawal { client: 1, tx: 3, amount: dec("10") });
+        assert_eq!(
+            result,
+            Err(LedgerError::NotEnoughFunds {
+                client: 1,
+                requested: dec("10"),
+                available: dec("2"),
+            })
+        );
     }
 }
@@ -1,83 +1,190 @@
 mod ledger;
 
-use crate::ledger::{Transaction, Ledger};
-use csv::{ReaderBuilder, Writer};
+use crate::ledger::{Ledger, LedgerError, Transaction};
+use csv::{DeserializeRecordsIntoIter, ReaderBuilder, Writer};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
 use std::io;
 use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
 
-fn main() {
-    let args = std::env::args();
-
-    let args = args.collect::<Vec<String>>();
+type Records = DeserializeRecordsIntoIter<File, Transaction>;
 
-    if args.len() > 2 {
-        eprintln!("Warning: Extra arguments will be ignored");
-    }
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
 
-    let file_path = if let Some(file_path) = args.get(1) {
-        Path::new(file_path)
-    } else {
-        eprintln!("A path to a CSV file must be provided.");
-        std::process::exit(1);
-    };
+    let Args {
+        file_path,
+        num_threads,
+        allow_negative_balances,
+    } = parse_args(&args);
+    let file_path = Path::new(&file_path);
 
     if !file_path.exists() {
         eprintln!("File \"{}\" not found.", file_path.to_string_lossy());
         std::process::exit(1);
     }
 
-    let mut reader = ReaderBuilder::new()
+    let reader = ReaderBuilder::new()
         .trim(csv::Trim::All)
+        // Dispute/resolve/chargeback rows may omit the trailing `amount` column,
+        // so accept rows with fewer fields than the header.
+        .flexible(true)
         .from_path(file_path)
         .unwrap_or_else(|e| {
             eprintln!("Failed to open CSV file: {}", e);
             std::process::exit(1);
         });
 
-    let records = reader.deserialize::<Transaction>();
+    let records = reader.into_deserialize::<Transaction>();
 
-    let mut ledger = Ledger::new();
+    let ledger = if num_threads > 1 {
+        process_parallel(records, num_threads, allow_negative_balances)
+    } else {
+        process_serial(records, allow_negative_balances)
+    };
 
-    for record in records {
-        let result = record
-            .map_err(|err| err.to_string())
-            .and_then(|transaction: Transaction| ledger.handle_new_transaction(&transaction));
-        if let Err(e) = result {
-            eprintln!("Warning: {}", e);
+    let mut csv_writer = Writer::from_writer(io::stdout());
+    if let Err(e) = ledger.write_csv(&mut csv_writer) {
+        eprintln!("Failed to write CSV output: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Parsed command-line configuration.
+struct Args {
+    file_path: String,
+    num_threads: usize,
+    allow_negative_balances: bool,
+}
+
+/// Parse the command line. The first non-flag argument is the input file;
+/// `--threads N` (default 1) selects the number of worker shards, and `--strict`
+/// rejects disputes that would drive an account negative rather than allowing it.
+fn parse_args(args: &[String]) -> Args {
+    let mut file_path = None;
+    let mut num_threads = 1usize;
+    let mut allow_negative_balances = true;
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--threads" => {
+                let value = iter.next().unwrap_or_else(|| {
+                    eprintln!("--threads requires a value");
+                    std::process::exit(1);
+                });
+                num_threads = value.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid value for --threads: {}", value);
+                    std::process::exit(1);
+                });
+                if num_threads == 0 {
+                    eprintln!("--threads must be at least 1");
+                    std::process::exit(1);
+                }
+            }
+            "--strict" => allow_negative_balances = false,
+            _ if file_path.is_none() => file_path = Some(arg.clone()),
+            _ => eprintln!("Warning: extra argument \"{}\" will be ignored", arg),
         }
     }
 
-    let mut csv_writer = Writer::from_writer(io::stdout());
-    if let Err(e) = csv_writer.write_record(["client", "available", "held", "total", "locked"]) {
-        eprintln!("Failed to write CSV header: {}", e);
-        // Exit if we're not able to write the CSV
+    let file_path = file_path.unwrap_or_else(|| {
+        eprintln!("A path to a CSV file must be provided.");
         std::process::exit(1);
+    });
+
+    Args {
+        file_path,
+        num_threads,
+        allow_negative_balances,
     }
+}
 
-    for (client_id, account_status) in ledger.client_accounts() {
-        if let Err(e) = csv_writer.write_record([
-            client_id.to_string(),
-            account_status.available.to_string(),
-            account_status.held.to_string(),
-            account_status.total.to_string(),
-            account_status.locked.to_string(),
-        ]) {
-            eprintln!(
-                "Error writing the following line to the CSV row: {}, {}, {}, {}, {}. Error: {}",
-                client_id.to_string(),
-                account_status.available.to_string(),
-                account_status.held.to_string(),
-                account_status.total.to_string(),
-                account_status.locked.to_string(),
-                e.to_string()
-            );
-            // Exit if we're not able to write the CSV
-            std::process::exit(1);
+/// Single-threaded fallback: feed every record through one ledger in order.
+fn process_serial(records: Records, allow_negative_balances: bool) -> Ledger {
+    let mut ledger = Ledger::with_negative_balances(allow_negative_balances);
+    for record in records {
+        match record {
+            Err(e) => eprintln!("Warning: skipping malformed record: {}", e),
+            Ok(transaction) => apply(&mut ledger, &transaction),
         }
     }
+    ledger
+}
 
-    if let Err(e) = csv_writer.flush() {
-        eprintln!("Failed to flush CSV output: {}", e);
-        std::process::exit(1);
+/// Partition the stream by `client % num_threads` into independent worker
+/// shards, each owning a private ledger and processing its own ordered
+/// substream, then merge the per-shard account maps. Routing by client keeps
+/// every transaction for a given client on one shard, so per-client ordering
+/// (a dispute always follows its deposit) is preserved.
+///
+/// Serial mode keys its `transactions` map globally, so it rejects a deposit or
+/// withdrawal whose id was already used as a duplicate; a per-shard ledger only
+/// sees its own slice of the stream and cannot. The routing loop below — which
+/// is already single-threaded — therefore runs the same global duplicate-id
+/// rejection before dispatching, so a reused id is dropped (never credited on
+/// two shards) and parallel output stays identical to serial.
+fn process_parallel(records: Records, num_threads: usize, allow_negative_balances: bool) -> Ledger {
+    let mut senders = Vec::with_capacity(num_threads);
+    let mut handles = Vec::with_capacity(num_threads);
+
+    for _ in 0..num_threads {
+        let (sender, receiver) = mpsc::channel::<Transaction>();
+        senders.push(sender);
+        handles.push(thread::spawn(move || {
+            let mut ledger = Ledger::with_negative_balances(allow_negative_balances);
+            for transaction in receiver {
+                apply(&mut ledger, &transaction);
+            }
+            ledger.into_client_accounts()
+        }));
+    }
+
+    let mut seen_tx_ids: HashSet<u32> = HashSet::new();
+    for record in records {
+        match record {
+            Err(e) => eprintln!("Warning: skipping malformed record: {}", e),
+            Ok(transaction) => {
+                // A deposit/withdrawal id reused anywhere in the stream is the
+                // same duplicate serial mode rejects; drop it before it reaches
+                // a shard so the two modes agree.
+                if transaction.introduces_tx_id() && !seen_tx_ids.insert(transaction.tx()) {
+                    eprintln!("Warning: {}", LedgerError::DuplicateTx(transaction.tx()));
+                    continue;
+                }
+                let shard = transaction.client() as usize % num_threads;
+                // The receiver lives until the channel is drained, so send cannot fail.
+                let _ = senders[shard].send(transaction);
+            }
+        }
+    }
+    drop(senders);
+
+    let mut accounts = HashMap::new();
+    for handle in handles {
+        accounts.extend(handle.join().expect("worker thread panicked"));
+    }
+    Ledger::from_accounts(accounts)
+}
+
+/// Apply one transaction to `ledger`, reporting any ledger error as a warning.
+fn apply(ledger: &mut Ledger, transaction: &Transaction) {
+    if let Err(e) = ledger.handle_new_transaction(transaction) {
+        match e {
+            // A frozen account is a hard stop for that client, but the rest of
+            // the stream is still worth processing, so every ledger error is a
+            // per-record warning rather than a fatal condition.
+            LedgerError::FrozenAccount(_)
+            | LedgerError::NotEnoughFunds { .. }
+            | LedgerError::UnknownTx(_)
+            | LedgerError::WrongClientForTx { .. }
+            | LedgerError::InvalidStateTransition { .. }
+            | LedgerError::AlreadyChargedBack(_)
+            | LedgerError::DuplicateTx(_)
+            | LedgerError::MissingAmount(_)
+            | LedgerError::UnexpectedAmount(_) => eprintln!("Warning: {}", e),
+        }
     }
 }